@@ -3,8 +3,10 @@
 mod condvar;
 mod mutex;
 mod semaphore;
+mod spin;
 
 pub use condvar::Condvar;
 pub use mutex::{Mutex, MutexBlocking, MutexSpin};
 pub use semaphore::Semaphore;
+pub use spin::{SpinMutex, SpinMutexGuard};
 pub use up::{UPIntrFreeCell, UPIntrRefMut};
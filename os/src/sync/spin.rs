@@ -0,0 +1,69 @@
+//! A real cross-core spinlock.
+//!
+//! [`UPIntrFreeCell`] only disables local interrupts on the current hart; it
+//! guarantees nothing once a second hart can concurrently reach the same
+//! cell, which [`crate::task::manager`]'s per-hart queues became reachable
+//! once work-stealing let one hart index into another's `TaskManager`.
+//! `SpinMutex` instead spins on an atomic flag, so it's safe to share across
+//! harts. It exposes the same `exclusive_access()` name as `UPIntrFreeCell`
+//! so call sites that move between the two don't need to change.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Interior mutability guarded by a spinlock instead of disabling local
+/// interrupts, so it's sound to share across harts.
+pub struct SpinMutex<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+
+impl<T> SpinMutex<T> {
+    /// Wrap `data` behind a new spinlock
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Spin until the lock is free, then exclusively access the data
+    pub fn exclusive_access(&self) -> SpinMutexGuard<'_, T> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        SpinMutexGuard { lock: self }
+    }
+}
+
+/// RAII guard returned by [`SpinMutex::exclusive_access`]; releases the lock
+/// on drop.
+pub struct SpinMutexGuard<'a, T> {
+    lock: &'a SpinMutex<T>,
+}
+
+impl<'a, T> Deref for SpinMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for SpinMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for SpinMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,68 @@
+//! Implementation of syscalls
+//!
+//! The single entry point to all system calls, [`syscall()`], is called by
+//! `trap_handler` (see `crate::trap`) once it decodes a `Trap::Exception(Exception::UserEnvCall)`.
+//! It dispatches to the relevant handler based on `syscall_id`, which is
+//! read out of `cx.x[17]` the way RISC-V's calling convention for `ecall`
+//! expects; the four argument registers are passed through as `args`.
+
+mod net;
+mod process;
+
+use net::sys_connect;
+use process::*;
+
+/// futex(2)
+const SYSCALL_FUTEX: usize = 98;
+/// exit(2)
+const SYSCALL_EXIT: usize = 93;
+/// sched_yield(2)
+const SYSCALL_YIELD: usize = 124;
+/// kill(2)
+const SYSCALL_KILL: usize = 129;
+/// rt_sigaction(2)
+const SYSCALL_SIGACTION: usize = 134;
+/// rt_sigprocmask(2)
+const SYSCALL_SIGPROCMASK: usize = 135;
+/// rt_sigreturn(2)
+const SYSCALL_SIGRETURN: usize = 139;
+/// Lab-specific: set the calling task's stride-scheduling priority
+const SYSCALL_SET_PRIORITY: usize = 140;
+/// gettimeofday(2)
+const SYSCALL_GET_TIME: usize = 169;
+/// brk(2), repurposed here as the lab's single-arg `sbrk`
+const SYSCALL_SBRK: usize = 214;
+/// munmap(2)
+const SYSCALL_MUNMAP: usize = 215;
+/// fork(2) / clone(2), depending on `args[0]`'s `CloneFlags`
+const SYSCALL_FORK: usize = 220;
+/// mmap(2)
+const SYSCALL_MMAP: usize = 222;
+/// Lab-specific: report the calling task's status/syscall counts/runtime
+const SYSCALL_TASK_INFO: usize = 410;
+/// Lab-specific: UDP `connect`
+const SYSCALL_CONNECT: usize = 2000;
+
+/// Handle a syscall exception, dispatching to the relevant handler based on
+/// `syscall_id`. `args` holds up to four raw argument registers; each arm
+/// casts them to whatever type the handler actually expects.
+pub fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
+    match syscall_id {
+        SYSCALL_EXIT => sys_exit(args[0] as i32),
+        SYSCALL_YIELD => sys_yield(),
+        SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
+        SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
+        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
+        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_SBRK => sys_sbrk(args[0] as i32),
+        SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
+        SYSCALL_FUTEX => sys_futex(args[0], args[1] as u32, args[2] as u32),
+        SYSCALL_FORK => sys_fork(args[0] as u32),
+        SYSCALL_KILL => sys_kill(args[0], args[1]),
+        SYSCALL_SIGACTION => sys_sigaction(args[0] as i32, args[1], args[2]),
+        SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0] as u32),
+        SYSCALL_SIGRETURN => sys_sigreturn(),
+        SYSCALL_CONNECT => sys_connect(args[0] as u32, args[1] as u16, args[2] as u16),
+        _ => panic!("Unsupported syscall_id: {}", syscall_id),
+    }
+}
@@ -1,10 +1,14 @@
 //! Process management syscalls
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use crate::{
     config::MAX_SYSCALL_NUM,
     task::{
-        change_program_brk, exit_current_and_run_next, suspend_current_and_run_next, TaskStatus, current_user_token, TASK_MANAGER,
+        block_current_and_run_next, change_program_brk, current_process, current_task,
+        current_trap_cx, exit_current_and_run_next, futex_wait_if_unchanged, futex_wake,
+        insert_into_pid2process_with_flags, kill, suspend_current_and_run_next, CloneFlags,
+        FutexKey, SignalFlags, TaskStatus, current_user_token, TASK_MANAGER,
     }, mm::{page_table::PageTable, VirtAddr}, timer::get_time_us,
 };
 
@@ -101,6 +105,150 @@ pub fn sys_munmap(_start: usize, _len: usize) -> isize {
     trace!("kernel: sys_munmap NOT IMPLEMENTED YET!");
     TASK_MANAGER.munmap(_start, _len)
 }
+/// Set the calling task's stride-scheduling priority. `priority` must be
+/// >= 2 so `BIG_STRIDE / priority` stays finite; returns -1 otherwise.
+pub fn sys_set_priority(priority: isize) -> isize {
+    trace!("kernel: sys_set_priority");
+    if priority < 2 {
+        return -1;
+    }
+    current_task().unwrap().set_priority(priority as usize);
+    priority
+}
+
+/// `futex(2)` operation codes we support.
+pub const FUTEX_WAIT: u32 = 0;
+/// `futex(2)` operation codes we support.
+pub const FUTEX_WAKE: u32 = 1;
+
+/// Minimal `futex(2)`: `FUTEX_WAIT` atomically checks `*uaddr == val` and,
+/// if it still holds, blocks the caller on that word's futex wait queue;
+/// `FUTEX_WAKE` wakes up to `val` waiters on it. Gives userspace
+/// mutexes/condvars a blocking primitive instead of having to spin.
+pub fn sys_futex(uaddr: usize, op: u32, val: u32) -> isize {
+    trace!("kernel: sys_futex");
+    let token = current_user_token();
+    let page_table = PageTable::from_token(token);
+    let word_pa = page_table.translate_va(VirtAddr::from(uaddr)).unwrap();
+    let pid = current_task()
+        .unwrap()
+        .process
+        .upgrade()
+        .unwrap()
+        .getpid();
+    let key = FutexKey { pid, vaddr: uaddr };
+    match op {
+        FUTEX_WAIT => {
+            let enqueued = unsafe {
+                futex_wait_if_unchanged(
+                    key,
+                    Arc::clone(&current_task().unwrap()),
+                    word_pa.0 as *const u32,
+                    val,
+                )
+            };
+            if !enqueued {
+                return -1;
+            }
+            block_current_and_run_next();
+            0
+        }
+        FUTEX_WAKE => futex_wake(key, val as usize) as isize,
+        _ => -1,
+    }
+}
+
+/// `clone(2)`-style fork. With no flags this is a plain `fork()`: the child
+/// is a brand-new process with its own copied `MemorySet` and fd table. Any
+/// of `CLONE_VM`/`CLONE_FILES` instead shares the parent's `MemorySet`/fd
+/// table with the child, and `CLONE_THREAD` makes the new task join the
+/// parent's own process as another thread rather than becoming a process of
+/// its own — in that case there's no fresh pid to hand back, so the parent's
+/// own pid is returned instead of a distinct thread id.
+pub fn sys_fork(flags: u32) -> isize {
+    trace!("kernel: sys_fork");
+    let flags = CloneFlags::from_bits_truncate(flags);
+    let parent = current_process();
+    let parent_pid = parent.getpid();
+    let (child_process, child_task) = parent.fork(
+        flags.contains(CloneFlags::CLONE_VM),
+        flags.contains(CloneFlags::CLONE_FILES),
+        flags.contains(CloneFlags::CLONE_THREAD),
+    );
+    let child_pid = child_process.getpid();
+    insert_into_pid2process_with_flags(
+        flags,
+        parent_pid,
+        child_pid,
+        child_process,
+        Arc::clone(&child_task),
+    );
+    child_pid as isize
+}
+
+/// Send `signum` to the process identified by `pid`. Its tasks blocked in a
+/// futex wait are woken so they can observe the signal on their next check;
+/// tasks blocked anywhere else stay blocked.
+pub fn sys_kill(pid: usize, signum: usize) -> isize {
+    trace!("kernel: sys_kill");
+    kill(pid, signum)
+}
+
+/// Install a new handler for `signum` on the current process, handing back
+/// the previous one through `old_action` the way `sigaction(2)` does.
+pub fn sys_sigaction(signum: i32, action: usize, old_action: usize) -> isize {
+    trace!("kernel: sys_sigaction");
+    if !(1..=31).contains(&signum) {
+        return -1;
+    }
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let token = current_user_token();
+    let page_table = PageTable::from_token(token);
+    if old_action != 0 {
+        let prev = inner.signal_handlers.get(&signum).copied().unwrap_or(0);
+        let dst = page_table.translate_va(VirtAddr::from(old_action)).unwrap();
+        unsafe {
+            *(dst.0 as *mut usize) = prev;
+        }
+    }
+    if action != 0 {
+        let src = page_table.translate_va(VirtAddr::from(action)).unwrap();
+        let handler = unsafe { *(src.0 as *const usize) };
+        inner.signal_handlers.insert(signum, handler);
+    }
+    0
+}
+
+/// Replace the current process's signal mask, returning the previous one
+/// so the caller can restore it later.
+pub fn sys_sigprocmask(mask: u32) -> isize {
+    trace!("kernel: sys_sigprocmask");
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old = inner.signal_mask.bits();
+    inner.signal_mask = SignalFlags::from_bits_truncate(mask);
+    old as isize
+}
+
+/// Return from a signal handler by restoring the trap context that
+/// `trap_handler`'s signal dispatch stashed in the task's `signal_saved_cx`
+/// before jumping to the handler; returns -1 if no context is saved. The
+/// context is kept in that single per-task slot, not on the user stack, so
+/// a second delivery before `sys_sigreturn` replaces the first saved one.
+pub fn sys_sigreturn() -> isize {
+    trace!("kernel: sys_sigreturn");
+    let task = current_task().unwrap();
+    let saved = task.inner_exclusive_access().signal_saved_cx.take();
+    match saved {
+        Some(saved_cx) => {
+            *current_trap_cx() = saved_cx;
+            current_trap_cx().x[10] as isize
+        }
+        None => -1,
+    }
+}
+
 /// change data segment size
 pub fn sys_sbrk(size: i32) -> isize {
     trace!("kernel: sys_sbrk");
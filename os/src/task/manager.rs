@@ -2,57 +2,270 @@
 //!
 //! It is only used to manage processes and schedule process based on ready queue.
 //! Other CPU process monitoring functions are in Processor.
+//!
+//! There is one [`TaskManager`] per hart (see [`TASK_MANAGERS`]), so normal
+//! scheduling never contends across cores; `fetch_task` only reaches into a
+//! remote hart's queue to steal work when its own is empty.
 
-use super::{ProcessControlBlock, TaskControlBlock, TaskStatus};
-use crate::sync::UPIntrFreeCell;
+use super::{
+    ProcessControlBlock, SchedPolicy, SignalFlags, TaskControlBlock, TaskStatus, MAX_RT_PRIORITY,
+    SCHED_LATENCY,
+};
+use crate::board::wake_hart;
+use crate::config::MAX_CPUS;
+use crate::sbi::hart_id;
+use crate::sync::SpinMutex;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::bitflags;
 use lazy_static::*;
 
+bitflags! {
+    /// Linux-style flags accepted by `sys_fork`'s `flags` argument, letting
+    /// it behave like `clone(2)` instead of a plain `fork()`.
+    pub struct CloneFlags: u32 {
+        /// Share the parent's `MemorySet` instead of copying it
+        const CLONE_VM = 1 << 8;
+        /// Share the parent's fd table instead of copying it
+        const CLONE_FILES = 1 << 10;
+        /// The new task joins the parent's process as another thread
+        /// instead of becoming a process of its own
+        const CLONE_THREAD = 1 << 16;
+    }
+}
+
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// One FIFO queue per real-time priority level (0 = highest); always
+    /// drained before any `Stride`/`Normal` task is allowed to run.
+    realtime_queues: Vec<VecDeque<Arc<TaskControlBlock>>>,
+    /// `Stride`-scheduled tasks (i.e. ones that called `sys_set_priority`),
+    /// scheduled by smallest accumulated `stride`; drained ahead of
+    /// `Normal` tasks but behind real-time ones.
+    stride_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// `Normal` tasks, scheduled CFS-style by smallest `vruntime`.
+    normal_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// The largest `vruntime` handed out so far; used to clamp a
+    /// newly-woken task so it can't start far enough behind to starve
+    /// everyone else once it's finally picked.
+    min_vruntime: usize,
 }
 
-/// A simple FIFO scheduler.
+/// A policy-driven scheduler: real-time (`Fifo`/`RoundRobin`) tasks are
+/// strict FIFO per priority level and always run before `Stride` tasks,
+/// which in turn always run before `Normal` tasks (scheduled CFS-style by
+/// virtual runtime).
 impl TaskManager {
     pub fn new() -> Self {
         Self {
-            ready_queue: VecDeque::new(),
+            realtime_queues: (0..MAX_RT_PRIORITY).map(|_| VecDeque::new()).collect(),
+            stride_queue: VecDeque::new(),
+            normal_queue: VecDeque::new(),
+            min_vruntime: 0,
         }
     }
-    /// Add process back to ready queue
+    /// Add process back to ready queue, routed to its scheduling class
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
+        let mut inner = task.inner_exclusive_access();
+        if inner.is_realtime() {
+            let level = inner.rt_priority.min(MAX_RT_PRIORITY - 1);
+            drop(inner);
+            self.realtime_queues[level].push_back(task);
+        } else if inner.sched_policy == SchedPolicy::Stride {
+            drop(inner);
+            self.stride_queue.push_back(task);
+        } else {
+            // a task that's been asleep a while must not start so far
+            // behind min_vruntime that it gets starved once it wakes up
+            let floor = self.min_vruntime.saturating_sub(SCHED_LATENCY);
+            if inner.vruntime < floor {
+                inner.vruntime = floor;
+            }
+            drop(inner);
+            self.normal_queue.push_back(task);
+        }
     }
-    /// Take a process out of the ready queue
+    /// Take a runnable real-time task if there is one, else the `Stride`
+    /// task with the smallest `stride`, else the `Normal` task with the
+    /// smallest `vruntime`.
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+        for queue in self.realtime_queues.iter_mut() {
+            if let Some(task) = queue.pop_front() {
+                return Some(task);
+            }
+        }
+        if let Some((idx, _)) = self
+            .stride_queue
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                stride_cmp(
+                    a.inner_exclusive_access().stride,
+                    b.inner_exclusive_access().stride,
+                )
+            })
+        {
+            let task = self.stride_queue.remove(idx).unwrap();
+            let mut inner = task.inner_exclusive_access();
+            inner.stride = inner.stride.wrapping_add(inner.pass);
+            drop(inner);
+            return Some(task);
+        }
+        let (idx, _) = self
+            .normal_queue
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, t)| t.inner_exclusive_access().vruntime)?;
+        let task = self.normal_queue.remove(idx).unwrap();
+        self.min_vruntime = self.min_vruntime.max(task.inner_exclusive_access().vruntime);
+        Some(task)
     }
     pub fn remove(&mut self, task: Arc<TaskControlBlock>) {
+        for queue in self.realtime_queues.iter_mut() {
+            if let Some((id, _)) = queue
+                .iter()
+                .enumerate()
+                .find(|(_, t)| Arc::as_ptr(t) == Arc::as_ptr(&task))
+            {
+                queue.remove(id);
+                return;
+            }
+        }
+        if let Some((id, _)) = self
+            .stride_queue
+            .iter()
+            .enumerate()
+            .find(|(_, t)| Arc::as_ptr(t) == Arc::as_ptr(&task))
+        {
+            self.stride_queue.remove(id);
+            return;
+        }
         if let Some((id, _)) = self
-            .ready_queue
+            .normal_queue
             .iter()
             .enumerate()
             .find(|(_, t)| Arc::as_ptr(t) == Arc::as_ptr(&task))
         {
-            self.ready_queue.remove(id);
+            self.normal_queue.remove(id);
         }
     }
+    /// Total number of ready tasks queued on this hart; other harts use
+    /// this to pick the most-loaded queue to steal from.
+    pub fn len(&self) -> usize {
+        self.realtime_queues.iter().map(VecDeque::len).sum::<usize>()
+            + self.stride_queue.len()
+            + self.normal_queue.len()
+    }
+    /// Steal one task this manager holds that `hart`'s `cpu_affinity` mask
+    /// allows it to run, preferring real-time tasks (by priority level),
+    /// then `Stride` tasks (by smallest `stride`), then `Normal` ones (by
+    /// smallest `vruntime`) so a steal doesn't skip the ageing logic in
+    /// `fetch`. Returns `None` if nothing here is stealable by `hart`.
+    fn steal_for(&mut self, hart: usize) -> Option<Arc<TaskControlBlock>> {
+        let allowed = |task: &Arc<TaskControlBlock>| {
+            task.inner_exclusive_access().cpu_affinity & (1 << hart) != 0
+        };
+        for queue in self.realtime_queues.iter_mut() {
+            if let Some(idx) = queue.iter().position(allowed) {
+                return queue.remove(idx);
+            }
+        }
+        if let Some(idx) = self
+            .stride_queue
+            .iter()
+            .filter(|t| allowed(t))
+            .min_by(|a, b| {
+                stride_cmp(
+                    a.inner_exclusive_access().stride,
+                    b.inner_exclusive_access().stride,
+                )
+            })
+            .and_then(|winner| {
+                self.stride_queue
+                    .iter()
+                    .position(|t| Arc::as_ptr(t) == Arc::as_ptr(winner))
+            })
+        {
+            let task = self.stride_queue.remove(idx)?;
+            let mut inner = task.inner_exclusive_access();
+            inner.stride = inner.stride.wrapping_add(inner.pass);
+            drop(inner);
+            return Some(task);
+        }
+        let idx = self
+            .normal_queue
+            .iter()
+            .filter(|t| allowed(t))
+            .min_by_key(|t| t.inner_exclusive_access().vruntime)
+            .and_then(|winner| {
+                self.normal_queue
+                    .iter()
+                    .position(|t| Arc::as_ptr(t) == Arc::as_ptr(winner))
+            })?;
+        let task = self.normal_queue.remove(idx)?;
+        self.min_vruntime = self.min_vruntime.max(task.inner_exclusive_access().vruntime);
+        Some(task)
+    }
+}
+
+/// Compare two stride values the way the stride-scheduling algorithm needs
+/// to: `stride` wraps around `usize`, so a plain `<` breaks once a task's
+/// stride has wrapped past a slower-accumulating one's. Comparing the
+/// wrapping signed difference instead stays correct as long as no two
+/// tasks' strides are ever more than `usize::MAX / 2` apart, which holds
+/// given `BIG_STRIDE`'s size relative to realistic priority values.
+fn stride_cmp(a: usize, b: usize) -> core::cmp::Ordering {
+    (a as isize).wrapping_sub(b as isize).cmp(&0)
+}
+
+/// Account for `delta_time` of CPU time just consumed by `task`, advancing
+/// its virtual runtime CFS-style: a lighter (lower-weight, "nicer") task's
+/// vruntime grows faster, so it falls behind and gets scheduled less often
+/// relative to heavier tasks. No-op for tasks outside the `Normal` class.
+pub fn update_vruntime(task: &Arc<TaskControlBlock>, delta_time: usize) {
+    let mut inner = task.inner_exclusive_access();
+    if inner.sched_policy == SchedPolicy::Normal {
+        let weight = inner.weight;
+        inner.vruntime += delta_time * super::NICE_0_WEIGHT / weight;
+    }
 }
 
 lazy_static! {
-    /// TASK_MANAGER instance through lazy_static!
-    pub static ref TASK_MANAGER: UPIntrFreeCell<TaskManager> =
-        unsafe { UPIntrFreeCell::new(TaskManager::new()) };
-    /// PID2PCB instance (map of pid to pcb)
-    pub static ref PID2PCB: UPIntrFreeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
-        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+    /// One ready-queue set per hart. Each hart's scheduler mostly only
+    /// touches its own `TASK_MANAGERS[hart_id()]`, but work-stealing makes
+    /// every entry genuinely reachable from every hart, so this is guarded
+    /// by a real cross-core [`SpinMutex`] rather than the uniprocessor-only
+    /// `UPIntrFreeCell`.
+    pub static ref TASK_MANAGERS: [SpinMutex<TaskManager>; MAX_CPUS] =
+        core::array::from_fn(|_| SpinMutex::new(TaskManager::new()));
+    /// PID2PCB instance (map of pid to pcb); reachable from any hart via
+    /// `pid2process`/`kill`, so it needs the same cross-core `SpinMutex`.
+    pub static ref PID2PCB: SpinMutex<BTreeMap<usize, Arc<ProcessControlBlock>>> =
+        SpinMutex::new(BTreeMap::new());
 }
 
-/// Add a task to ready queue
+/// Add a task to the current hart's ready queue, or, if the task's
+/// `cpu_affinity` excludes this hart, to the lowest-numbered hart it allows
 pub fn add_task(task: Arc<TaskControlBlock>) {
     //trace!("kernel: TaskManager::add_task");
-    TASK_MANAGER.exclusive_access().add(task);
+    let hart = hart_id();
+    let affinity = task.inner_exclusive_access().cpu_affinity;
+    let target = if affinity & (1 << hart) != 0 {
+        hart
+    } else {
+        (0..MAX_CPUS).find(|&h| affinity & (1 << h) != 0).unwrap_or(hart)
+    };
+    add_task_on(target, task);
+}
+
+/// Add a task to a specific hart's ready queue, e.g. to honour
+/// `cpu_affinity` when a task is first spawned. Kicks that hart via IPI if
+/// it's idle so it doesn't have to wait for its next timer tick to notice.
+pub fn add_task_on(hart: usize, task: Arc<TaskControlBlock>) {
+    TASK_MANAGERS[hart].exclusive_access().add(task);
+    if hart != hart_id() {
+        wake_hart(hart);
+    }
 }
 
 /// Wake up a task
@@ -64,16 +277,38 @@ pub fn wakeup_task(task: Arc<TaskControlBlock>) {
     add_task(task);
 }
 
-/// Remove a task from the ready queue
+/// Remove a task from whichever hart's ready queue currently holds it
 pub fn remove_task(task: Arc<TaskControlBlock>) {
     //trace!("kernel: TaskManager::remove_task");
-    TASK_MANAGER.exclusive_access().remove(task);
+    for manager in TASK_MANAGERS.iter() {
+        manager.exclusive_access().remove(Arc::clone(&task));
+    }
 }
 
-/// Fetch a task out of the ready queue
+/// Fetch a task for the current hart to run: drain its own ready queue
+/// first, and only if that's empty, steal one task this hart is allowed to
+/// run from another hart. Candidates are tried most-loaded first, but a
+/// candidate's queue can be nonempty and still have nothing `steal_for`
+/// will hand over (every task pinned away from this hart by `cpu_affinity`),
+/// so we fall through to the next-most-loaded hart instead of giving up
+/// after the first one.
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
     //trace!("kernel: TaskManager::fetch_task");
-    TASK_MANAGER.exclusive_access().fetch()
+    let hart = hart_id();
+    if let Some(task) = TASK_MANAGERS[hart].exclusive_access().fetch() {
+        return Some(task);
+    }
+    let mut candidates: Vec<usize> = (0..MAX_CPUS).filter(|&h| h != hart).collect();
+    candidates.sort_by_key(|&h| core::cmp::Reverse(TASK_MANAGERS[h].exclusive_access().len()));
+    for victim in candidates {
+        if TASK_MANAGERS[victim].exclusive_access().len() == 0 {
+            break;
+        }
+        if let Some(task) = TASK_MANAGERS[victim].exclusive_access().steal_for(hart) {
+            return Some(task);
+        }
+    }
+    None
 }
 
 /// Get process by pid
@@ -87,10 +322,191 @@ pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
     PID2PCB.exclusive_access().insert(pid, process);
 }
 
+/// Register a freshly-created task's process in `PID2PCB` according to the
+/// `clone(2)`-style `flags` passed to `sys_fork`, called from the tail of
+/// the process-creation path once the new `TaskControlBlock` exists.
+///
+/// Without `CLONE_THREAD` this behaves exactly like [`insert_into_pid2process`]:
+/// `child_process` is a brand-new process and gets its own `PID2PCB` entry.
+/// With `CLONE_THREAD`, `child_pid` was never allocated a fresh pid — instead
+/// `child_task` is pushed onto `parent_pid`'s existing `ProcessControlBlock`
+/// as another thread, so no new `PID2PCB` entry is made for it.
+pub fn insert_into_pid2process_with_flags(
+    flags: CloneFlags,
+    parent_pid: usize,
+    child_pid: usize,
+    child_process: Arc<ProcessControlBlock>,
+    child_task: Arc<TaskControlBlock>,
+) {
+    if flags.contains(CloneFlags::CLONE_THREAD) {
+        debug_assert_eq!(
+            child_process.getpid(),
+            parent_pid,
+            "CLONE_THREAD's child_process must be the parent process itself"
+        );
+        child_process.inner_exclusive_access().tasks.push(Some(child_task));
+    } else {
+        insert_into_pid2process(child_pid, child_process);
+    }
+}
+
 /// Remove item(pid, _some_pcb) from PDI2PCB map (called by exit_current_and_run_next)
 pub fn remove_from_pid2process(pid: usize) {
     let mut map = PID2PCB.exclusive_access();
     if map.remove(&pid).is_none() {
         panic!("cannot find pid {} in pid2task!", pid);
     }
+    drop(map);
+    futex_clear_process(pid);
+}
+
+/// Identifies a userspace futex word: the owning process's pid plus the
+/// word's virtual address, so two processes mapping different pages at the
+/// same address never collide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FutexKey {
+    pub pid: usize,
+    pub vaddr: usize,
+}
+
+lazy_static! {
+    /// Per-futex-word wait queues, analogous to DragonOS's `libs::futex`.
+    /// The caller (`sys_futex`) is responsible for atomically checking the
+    /// word's value before enqueuing onto this. A waiter can be woken by
+    /// `FUTEX_WAKE` running on a different hart than the one it blocked on,
+    /// so this needs the cross-core `SpinMutex`, not `UPIntrFreeCell`.
+    pub static ref FUTEX_QUEUES: SpinMutex<BTreeMap<FutexKey, VecDeque<Arc<TaskControlBlock>>>> =
+        SpinMutex::new(BTreeMap::new());
+}
+
+/// `FUTEX_WAIT`'s check-then-enqueue, done atomically under `FUTEX_QUEUES`'s
+/// lock: re-reads `*value_ptr` once the lock is held and, only if it still
+/// equals `expected`, pushes `task` onto `key`'s wait queue before releasing
+/// it. Without sharing a lock between this check and the enqueue, a
+/// `FUTEX_WAKE` landing between the caller's own unlocked read and the
+/// enqueue would see an empty queue, wake nobody, and leave `task` blocked
+/// forever -- the classic futex lost-wakeup. Returns `false` (caller must
+/// not block) if the value had already changed.
+///
+/// # Safety
+/// `value_ptr` must point to a valid, readable `u32` for the duration of
+/// this call.
+pub unsafe fn futex_wait_if_unchanged(
+    key: FutexKey,
+    task: Arc<TaskControlBlock>,
+    value_ptr: *const u32,
+    expected: u32,
+) -> bool {
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    if unsafe { *value_ptr } != expected {
+        return false;
+    }
+    queues.entry(key).or_insert_with(VecDeque::new).push_back(task);
+    true
+}
+
+/// `FUTEX_WAKE`: pop up to `max_count` waiters from `key`'s queue and hand
+/// each to [`wakeup_task`]. Returns how many were actually woken.
+pub fn futex_wake(key: FutexKey, max_count: usize) -> usize {
+    let mut to_wake = Vec::new();
+    {
+        let mut queues = FUTEX_QUEUES.exclusive_access();
+        if let Some(queue) = queues.get_mut(&key) {
+            while to_wake.len() < max_count {
+                match queue.pop_front() {
+                    Some(task) => to_wake.push(task),
+                    None => break,
+                }
+            }
+        }
+    }
+    let woken = to_wake.len();
+    for task in to_wake {
+        wakeup_task(task);
+    }
+    woken
+}
+
+/// `sys_kill`'s core: look `pid` up in `PID2PCB`, raise `signum` in its
+/// pending set, and wake any of its tasks that are parked in a futex wait
+/// queue so they can observe the new signal the next time they check.
+/// Tasks blocked anywhere else are not woken.
+/// Returns `-1` if `pid` doesn't name a live process or `signum` is out of
+/// range.
+pub fn kill(pid: usize, signum: usize) -> isize {
+    if !(1..=31).contains(&signum) {
+        return -1;
+    }
+    let Some(signal) = SignalFlags::from_bits(1 << signum) else {
+        return -1;
+    };
+    match pid2process(pid) {
+        Some(process) => {
+            let mut inner = process.inner_exclusive_access();
+            inner.pending.insert(signal);
+            for task in inner.tasks.iter().flatten() {
+                if task.inner_exclusive_access().task_status == TaskStatus::Blocked
+                    && futex_remove_task(task)
+                {
+                    wakeup_task(Arc::clone(task));
+                }
+            }
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Remove `task` from whichever `FUTEX_QUEUES` entry currently holds it, if
+/// any. Used by [`kill`] before force-waking a blocked task: without this, a
+/// later legitimate `FUTEX_WAKE` would find the task's `Arc` still sitting in
+/// the queue and call [`wakeup_task`] on it a second time, scheduling it
+/// twice. Blocked tasks parked elsewhere (e.g. a `Condvar`'s own wait queue,
+/// which isn't globally reachable from here) are left alone, so `kill` only
+/// force-wakes the futex-parked ones it can safely account for.
+fn futex_remove_task(task: &Arc<TaskControlBlock>) -> bool {
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    for queue in queues.values_mut() {
+        if let Some(idx) = queue
+            .iter()
+            .position(|t| Arc::as_ptr(t) == Arc::as_ptr(task))
+        {
+            queue.remove(idx);
+            return true;
+        }
+    }
+    false
+}
+
+/// Pop the lowest-numbered pending signal for `process` that isn't masked,
+/// removing it from `pending` so it's only delivered once. Called from
+/// `trap_handler`'s dispatch path on the way back to userspace.
+pub fn take_pending_signal(process: &Arc<ProcessControlBlock>) -> Option<SignalFlags> {
+    let mut inner = process.inner_exclusive_access();
+    let deliverable = inner.pending.bits() & !inner.signal_mask.bits();
+    if deliverable == 0 {
+        return None;
+    }
+    let bit = 1u32 << deliverable.trailing_zeros();
+    let signal = SignalFlags::from_bits(bit)?;
+    inner.pending.remove(signal);
+    Some(signal)
+}
+
+/// The lowest signal number set in a single-bit `SignalFlags` value, e.g.
+/// `9` for `SIGKILL`. Used to index `signal_handlers` and to report the
+/// signal number to a user handler.
+pub fn signal_number(signal: SignalFlags) -> i32 {
+    signal.bits().trailing_zeros() as i32
+}
+
+/// Drop every task belonging to `pid` from every futex wait queue, so a
+/// process that exits while a thread is parked in `FUTEX_WAIT` never leaves
+/// a dangling `Arc` behind.
+fn futex_clear_process(pid: usize) {
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    for queue in queues.values_mut() {
+        queue.retain(|task| task.process.upgrade().map(|p| p.getpid()) != Some(pid));
+    }
+    queues.retain(|_, queue| !queue.is_empty());
 }
@@ -1,12 +1,56 @@
 //! Types related to task management
 
-
-use alloc::{vec::Vec};
+use alloc::sync::Weak;
+use alloc::vec::Vec;
 
 use crate::config::MAX_SYSCALL_NUM;
+use crate::sync::{SpinMutex, SpinMutexGuard};
+use crate::trap::TrapContext;
+
+use super::{ProcessControlBlock, TaskContext};
+
+/// Track length for the stride-scheduling algorithm. Every ready task's
+/// `pass` is `BIG_STRIDE / priority`, so a task with lower priority
+/// accumulates stride faster and is picked less often.
+pub const BIG_STRIDE: usize = 0xFFFF_FFFF;
+
+/// Priority assigned to a task that has never called `sys_set_priority`.
+pub const DEFAULT_PRIORITY: usize = 16;
+
+/// Weight of a "nice 0" task; a `Normal` task's `vruntime` advances by
+/// `delta_time * NICE_0_WEIGHT / weight`, so a heavier task's vruntime grows
+/// more slowly and it gets picked more often.
+pub const NICE_0_WEIGHT: usize = 1024;
 
-use super::TaskContext;
+/// How far behind `min_vruntime` a newly-woken `Normal` task is allowed to
+/// start; caps the head start it would otherwise get over tasks that have
+/// been runnable (and accumulating vruntime) the whole time.
+pub const SCHED_LATENCY: usize = 6_000;
 
+/// The number of distinct real-time priority levels; level `0` is drained
+/// first.
+pub const MAX_RT_PRIORITY: usize = 100;
+
+/// Default `cpu_affinity`: every bit set, i.e. runnable on any hart.
+pub const CPU_AFFINITY_ALL: usize = usize::MAX;
+
+/// Scheduling class a task belongs to, mirroring Linux/DragonOS's
+/// `SchedPolicy`. Real-time classes are always drained before `Normal`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// Real-time, run-to-completion within its priority level
+    Fifo,
+    /// Real-time, round-robins with same-priority tasks
+    RoundRobin,
+    /// CFS-style, scheduled by virtual runtime
+    Normal,
+    /// Stride-scheduled by `set_priority`/`sys_set_priority`; scheduled
+    /// ahead of `Normal` tasks but behind real-time ones, by smallest
+    /// accumulated `stride`
+    Stride,
+    /// Alias for `Fifo`/`RoundRobin` callers that only care "is this RT"
+    RealTime,
+}
 
 #[allow(unused)]
 #[derive(Clone)]
@@ -26,8 +70,18 @@ impl TaskInfo {
 
 
 /// The task control block (TCB) of a task.
-#[derive(Clone)]
 pub struct TaskControlBlock {
+    /// The process this task belongs to
+    pub process: Weak<ProcessControlBlock>,
+    /// Mutable inner part, guarded so the scheduler can update it through
+    /// a bare `Arc<TaskControlBlock>`. A remote hart's `steal_for` reaches
+    /// into this directly, so it needs a real cross-core `SpinMutex`
+    /// rather than the uniprocessor-only `UPIntrFreeCell`.
+    inner: SpinMutex<TaskControlBlockInner>,
+}
+
+/// Mutable inner part of a [`TaskControlBlock`]
+pub struct TaskControlBlockInner {
     /// The task status in it's lifecycle
     pub task_status: TaskStatus,
     /// The task context
@@ -35,7 +89,60 @@ pub struct TaskControlBlock {
     /// The task info
     pub task_info: TaskInfo,
     ///
-    pub time : usize
+    pub time : usize,
+    /// Stride-scheduling priority, always >= 2 so `pass` stays finite
+    pub priority: usize,
+    /// Accumulated stride; `fetch()` always picks the ready task with the
+    /// smallest `stride`
+    pub stride: usize,
+    /// `BIG_STRIDE / priority`, added to `stride` every time this task is
+    /// scheduled
+    pub pass: usize,
+    /// Which scheduling class this task belongs to
+    pub sched_policy: SchedPolicy,
+    /// Real-time priority level (0 = highest), only meaningful when
+    /// `sched_policy` is `Fifo`/`RoundRobin`/`RealTime`
+    pub rt_priority: usize,
+    /// CFS-style weight derived from niceness; only meaningful when
+    /// `sched_policy` is `Normal`
+    pub weight: usize,
+    /// Virtual runtime; `Normal` tasks are scheduled by picking the
+    /// smallest one
+    pub vruntime: usize,
+    /// Bitmask of harts this task is allowed to run on (bit `i` = hart
+    /// `i`); `add`/work-stealing on [`super::TaskManager`] must respect it
+    pub cpu_affinity: usize,
+    /// The trap context saved when a signal handler was dispatched onto
+    /// this task, restored by `sys_sigreturn` once the handler returns
+    pub signal_saved_cx: Option<TrapContext>,
+}
+
+impl TaskControlBlock {
+    /// Exclusively access the mutable inner part
+    pub fn inner_exclusive_access(&self) -> SpinMutexGuard<'_, TaskControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+
+    /// Change this task's stride-scheduling priority, recomputing `pass`,
+    /// and switch it onto the `Stride` scheduling class so the new
+    /// priority actually affects scheduling order
+    pub fn set_priority(&self, priority: usize) {
+        let mut inner = self.inner_exclusive_access();
+        inner.priority = priority;
+        inner.pass = BIG_STRIDE / priority;
+        inner.sched_policy = SchedPolicy::Stride;
+    }
+}
+
+impl TaskControlBlockInner {
+    /// Whether this task belongs to a real-time scheduling class and must
+    /// be drained ahead of every `Normal` task
+    pub fn is_realtime(&self) -> bool {
+        matches!(
+            self.sched_policy,
+            SchedPolicy::Fifo | SchedPolicy::RoundRobin | SchedPolicy::RealTime
+        )
+    }
 }
 
 /// The status of a task
@@ -47,6 +154,9 @@ pub enum TaskStatus {
     Ready,
     /// running
     Running,
+    /// blocked, e.g. waiting on a futex or condition variable; not present
+    /// in any ready queue until something wakes it back up
+    Blocked,
     /// exited
     Exited,
 }
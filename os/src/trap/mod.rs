@@ -17,8 +17,9 @@ mod context;
 use crate::config::TRAMPOLINE;
 use crate::syscall::syscall;
 use crate::task::{
-    check_signals_of_current, current_add_signal, current_trap_cx, current_trap_cx_user_va,
-    current_user_token, exit_current_and_run_next, suspend_current_and_run_next, SignalFlags,
+    check_signals_of_current, current_add_signal, current_process, current_task, current_trap_cx,
+    current_trap_cx_user_va, current_user_token, exit_current_and_run_next, signal_number,
+    suspend_current_and_run_next, take_pending_signal, SignalFlags,
 };
 use crate::timer::{check_timer, set_next_trigger};
 use core::arch::{asm, global_asm};
@@ -128,9 +129,41 @@ pub fn trap_handler() -> ! {
         trace!("[kernel] trap_handler: .. check signals {}", msg);
         exit_current_and_run_next(errno);
     }
+    deliver_pending_process_signals();
     trap_return();
 }
 
+/// Dispatch one pending, unmasked signal for the current process before
+/// returning to userspace. A registered handler is entered by redirecting
+/// `sepc`/`a0` after stashing the interrupted trap context on the task so
+/// `sys_sigreturn` can restore it; `SIGKILL`/`SIGSEGV` with no handler kill
+/// the process, anything else with no handler is just dropped (ignored).
+fn deliver_pending_process_signals() {
+    let process = current_process();
+    let Some(signal) = take_pending_signal(&process) else {
+        return;
+    };
+    let handler = process
+        .inner_exclusive_access()
+        .signal_handlers
+        .get(&signal_number(signal))
+        .copied()
+        .unwrap_or(0);
+    if handler != 0 {
+        let task = current_task().unwrap();
+        let cx = current_trap_cx();
+        task.inner_exclusive_access().signal_saved_cx = Some(*cx);
+        let cx = current_trap_cx();
+        cx.x[10] = signal_number(signal) as usize;
+        cx.sepc = handler;
+    } else if signal.intersects(SignalFlags::SIGKILL | SignalFlags::SIGSEGV) {
+        drop(process);
+        exit_current_and_run_next(-(signal.bits() as i32));
+    }
+    // else: no handler installed and the signal isn't fatal by default
+    // (e.g. SIGCHLD) -- it was already popped off `pending`, just drop it
+}
+
 /// return to user space
 #[no_mangle]
 pub fn trap_return() -> ! {